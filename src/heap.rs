@@ -0,0 +1,293 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ffi::c_void;
+use core::ptr::NonNull;
+use ffi::*;
+
+use crate::MIN_ALIGN;
+
+#[cfg(feature = "nightly")]
+use core::alloc::{AllocError, Allocator};
+
+/// A scoped mimalloc heap, backed by a `mi_heap_t`.
+///
+/// Unlike [`MiMalloc`](crate::MiMalloc), which allocates out of mimalloc's shared default heap,
+/// a `MiHeap` owns its own arena: every allocation made through it is freed in one shot when the
+/// heap is dropped, via `mi_heap_destroy`. This is useful for request/response or other
+/// scope-bounded workloads where many small objects share a lifetime.
+///
+/// ## Usage
+/// ```rust,ignore
+/// use mimalloc::MiHeap;
+/// use core::alloc::{GlobalAlloc, Layout};
+///
+/// let heap = MiHeap::new();
+/// let layout = Layout::from_size_align(8, 8).unwrap();
+/// unsafe {
+///     let ptr = heap.alloc(layout);
+///     heap.dealloc(ptr, layout);
+/// }
+/// // All outstanding allocations made through `heap` are reclaimed here.
+/// drop(heap);
+/// ```
+/// mimalloc heaps can only allocate from the thread that created them (see the `mi_heap_new`
+/// docs upstream), so `MiHeap` is intentionally left `!Send`/`!Sync` via its raw pointer field.
+pub struct MiHeap(NonNull<mi_heap_t>);
+
+impl MiHeap {
+    /// Creates a new, empty heap.
+    #[inline]
+    pub fn new() -> MiHeap {
+        let raw = unsafe { mi_heap_new() };
+        MiHeap(NonNull::new(raw).expect("mi_heap_new returned a null heap"))
+    }
+
+    #[inline]
+    fn as_raw(&self) -> *mut mi_heap_t {
+        self.0.as_ptr()
+    }
+}
+
+impl Default for MiHeap {
+    #[inline]
+    fn default() -> MiHeap {
+        MiHeap::new()
+    }
+}
+
+impl Drop for MiHeap {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { mi_heap_destroy(self.as_raw()) };
+    }
+}
+
+unsafe impl GlobalAlloc for MiHeap {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        mi_heap_malloc_aligned(self.as_raw(), layout.size(), layout.align()) as *mut u8
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        mi_heap_zalloc_aligned(self.as_raw(), layout.size(), layout.align()) as *mut u8
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+            mi_free(ptr as *mut c_void);
+        } else {
+            mi_free_size_aligned(ptr as *mut c_void, layout.size(), layout.align());
+        }
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        mi_heap_realloc_aligned(self.as_raw(), ptr as *mut c_void, new_size, layout.align())
+            as *mut u8
+    }
+}
+
+#[cfg(feature = "nightly")]
+unsafe impl Allocator for MiHeap {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let raw = unsafe { mi_heap_malloc_aligned(self.as_raw(), layout.size(), layout.align()) }
+            as *mut u8;
+        let ptr = NonNull::new(raw).ok_or(AllocError)?;
+        let usable = unsafe { mi_usable_size(raw as *const c_void) };
+        Ok(NonNull::slice_from_raw_parts(ptr, usable))
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let raw = unsafe { mi_heap_zalloc_aligned(self.as_raw(), layout.size(), layout.align()) }
+            as *mut u8;
+        let ptr = NonNull::new(raw).ok_or(AllocError)?;
+        let usable = unsafe { mi_usable_size(raw as *const c_void) };
+        Ok(NonNull::slice_from_raw_parts(ptr, usable))
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+            mi_free(ptr.as_ptr() as *mut c_void);
+        } else {
+            mi_free_size_aligned(ptr.as_ptr() as *mut c_void, layout.size(), layout.align());
+        }
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(new_layout.size() >= old_layout.size());
+        let raw = mi_heap_realloc_aligned(
+            self.as_raw(),
+            ptr.as_ptr() as *mut c_void,
+            new_layout.size(),
+            new_layout.align(),
+        ) as *mut u8;
+        let ptr = NonNull::new(raw).ok_or(AllocError)?;
+        let usable = mi_usable_size(raw as *const c_void);
+        Ok(NonNull::slice_from_raw_parts(ptr, usable))
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(new_layout.size() >= old_layout.size());
+        let new_ptr = self.grow(ptr, old_layout, new_layout)?;
+        let raw = new_ptr.as_non_null_ptr().as_ptr();
+        raw.add(old_layout.size())
+            .write_bytes(0, new_ptr.len() - old_layout.size());
+        Ok(new_ptr)
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(new_layout.size() <= old_layout.size());
+        let raw = mi_heap_realloc_aligned(
+            self.as_raw(),
+            ptr.as_ptr() as *mut c_void,
+            new_layout.size(),
+            new_layout.align(),
+        ) as *mut u8;
+        let ptr = NonNull::new(raw).ok_or(AllocError)?;
+        let usable = mi_usable_size(raw as *const c_void);
+        Ok(NonNull::slice_from_raw_parts(ptr, usable))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_frees_allocated_memory() {
+        unsafe {
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.alloc(layout);
+            heap.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn it_destroys_the_heap_with_outstanding_allocations() {
+        unsafe {
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let heap = MiHeap::new();
+
+            let _ptr = heap.alloc(layout);
+            // Dropping the heap reclaims `_ptr` without an explicit dealloc.
+        }
+    }
+}
+
+#[cfg(all(test, feature = "nightly"))]
+mod allocator_api_tests {
+    use super::*;
+
+    #[test]
+    fn it_allocates_and_deallocates() {
+        unsafe {
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.allocate(layout).unwrap();
+            heap.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_and_deallocates_big_memory() {
+        unsafe {
+            let layout = Layout::from_size_align(1 << 20, 32).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.allocate(layout).unwrap();
+            heap.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_and_deallocates_zeroed_memory() {
+        unsafe {
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.allocate_zeroed(layout).unwrap();
+            heap.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_and_deallocates_zeroed_big_memory() {
+        unsafe {
+            let layout = Layout::from_size_align(1 << 20, 32).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.allocate_zeroed(layout).unwrap();
+            heap.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_grows_allocated_memory() {
+        unsafe {
+            let old_layout = Layout::from_size_align(8, 8).unwrap();
+            let new_layout = Layout::from_size_align(16, 8).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.allocate(old_layout).unwrap();
+            let ptr = heap
+                .grow(ptr.as_non_null_ptr(), old_layout, new_layout)
+                .unwrap();
+            heap.deallocate(ptr.as_non_null_ptr(), new_layout);
+        }
+    }
+
+    #[test]
+    fn it_grows_zeroed_allocated_memory() {
+        unsafe {
+            let old_layout = Layout::from_size_align(8, 8).unwrap();
+            let new_layout = Layout::from_size_align(16, 8).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.allocate(old_layout).unwrap();
+            let ptr = heap
+                .grow_zeroed(ptr.as_non_null_ptr(), old_layout, new_layout)
+                .unwrap();
+            heap.deallocate(ptr.as_non_null_ptr(), new_layout);
+        }
+    }
+
+    #[test]
+    fn it_shrinks_allocated_memory() {
+        unsafe {
+            let old_layout = Layout::from_size_align(1 << 20, 32).unwrap();
+            let new_layout = Layout::from_size_align(8, 32).unwrap();
+            let heap = MiHeap::new();
+
+            let ptr = heap.allocate(old_layout).unwrap();
+            let ptr = heap
+                .shrink(ptr.as_non_null_ptr(), old_layout, new_layout)
+                .unwrap();
+            heap.deallocate(ptr.as_non_null_ptr(), new_layout);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // Copyright 2019 Octavian Oncescu
 
 #![no_std]
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
 
 //! A drop-in global allocator wrapper around the [mimalloc](https://github.com/microsoft/mimalloc) allocator.
 //! Mimalloc is a general purpose, performance oriented allocator built by Microsoft.
@@ -22,13 +23,53 @@
 //! [dependencies]
 //! mimalloc = { version = "*", default-features = false }
 //! ```
+//!
+//! ## Usage as a per-collection allocator
+//! On nightly, enable the `nightly` feature to get an `Allocator` (allocator_api) impl on
+//! `MiMalloc`, so it can back an individual collection instead of the whole process:
+//! ```rust,ignore
+//! #![feature(allocator_api)]
+//! use mimalloc::MiMalloc;
+//!
+//! let v: Vec<u8, MiMalloc> = Vec::new_in(MiMalloc);
+//! ```
 
 extern crate libmimalloc_sys as ffi;
 
+mod heap;
+
+pub use heap::MiHeap;
+
 use core::alloc::{GlobalAlloc, Layout};
-use core::ffi::c_void;
+use core::ffi::{c_long, c_void};
 use ffi::*;
 
+#[cfg(feature = "nightly")]
+use core::alloc::{AllocError, Allocator};
+#[cfg(feature = "nightly")]
+use core::ptr::NonNull;
+
+// The minimum alignment guaranteed by the underlying `mi_malloc`/`mi_zalloc`/`mi_realloc`
+// entry points, mirroring the constant of the same name in `liballoc_system`. Requests at or
+// below this alignment (and no larger than the allocation itself) can skip the `*_aligned`
+// variants entirely.
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "arm",
+    target_arch = "mips",
+    target_arch = "powerpc"
+))]
+const MIN_ALIGN: usize = 8;
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "mips64",
+    target_arch = "powerpc64"
+))]
+const MIN_ALIGN: usize = 16;
+#[cfg(target_arch = "wasm32")]
+const MIN_ALIGN: usize = 8;
+
 #[cfg(any(
     all(feature="secure_full", any(feature="secure_1", feature="secure_2", feature="secure_3")),
     all(feature="secure_1", any(feature="secure_full", feature="secure_2", feature="secure_3")),
@@ -51,23 +92,185 @@ pub struct MiMalloc;
 unsafe impl GlobalAlloc for MiMalloc {
     #[inline]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        mi_malloc_aligned(layout.size(), layout.align()) as *mut u8
+        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+            mi_malloc(layout.size()) as *mut u8
+        } else {
+            mi_malloc_aligned(layout.size(), layout.align()) as *mut u8
+        }
     }
 
     #[inline]
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        mi_zalloc_aligned(layout.size(), layout.align()) as *mut u8
+        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+            mi_zalloc(layout.size()) as *mut u8
+        } else {
+            mi_zalloc_aligned(layout.size(), layout.align()) as *mut u8
+        }
     }
 
     #[inline]
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        mi_free(ptr as *mut c_void);
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+            mi_free(ptr as *mut c_void);
+        } else {
+            mi_free_size_aligned(ptr as *mut c_void, layout.size(), layout.align());
+        }
     }
 
     #[inline]
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        mi_realloc_aligned(ptr as *mut c_void, new_size, layout.align()) as *mut u8
+        if layout.align() <= MIN_ALIGN && layout.align() <= new_size {
+            mi_realloc(ptr as *mut c_void, new_size) as *mut u8
+        } else {
+            mi_realloc_aligned(ptr as *mut c_void, new_size, layout.align()) as *mut u8
+        }
+    }
+}
+
+impl MiMalloc {
+    /// Sets a mimalloc runtime option, overriding whatever was set via the `MIMALLOC_*`
+    /// environment variables or a previous call.
+    #[inline]
+    pub fn set_option(&self, option: mi_option_t, value: c_long) {
+        unsafe { mi_option_set(option, value) };
+    }
+
+    /// Reads back the current value of a mimalloc runtime option.
+    #[inline]
+    pub fn get_option(&self, option: mi_option_t) -> c_long {
+        unsafe { mi_option_get(option) }
+    }
+
+    /// Enables or disables mimalloc's `mi_option_eager_commit` runtime option.
+    #[inline]
+    pub fn eager_commit(&self, enable: bool) {
+        unsafe { mi_option_set_enabled(mi_option_eager_commit, enable) };
+    }
+
+    /// Sets the delay, in milliseconds, mimalloc waits before resetting (decommitting) a freed
+    /// memory page back to the OS.
+    #[inline]
+    pub fn reset_delay(&self, milliseconds: c_long) {
+        unsafe { mi_option_set(mi_option_reset_delay, milliseconds) };
+    }
+
+    /// Reserves `pages` huge OS pages (1GiB each), interleaved across `numa_nodes` NUMA nodes,
+    /// aborting the reservation after `timeout_msecs` if it has not completed. Returns `true` on
+    /// success.
+    #[inline]
+    pub fn reserve_huge_os_pages(&self, pages: usize, numa_nodes: usize, timeout_msecs: usize) -> bool {
+        unsafe { mi_reserve_huge_os_pages_interleave(pages, numa_nodes, timeout_msecs) == 0 }
+    }
+
+    /// Prints the current mimalloc allocation statistics to stderr (or `MIMALLOC_VERBOSE`'s
+    /// configured destination).
+    #[inline]
+    pub fn stats_print(&self) {
+        unsafe { mi_stats_print(core::ptr::null_mut()) };
+    }
+
+    /// Resets the mimalloc allocation statistics counters.
+    #[inline]
+    pub fn stats_reset(&self) {
+        unsafe { mi_stats_reset() };
+    }
+
+    /// Returns the usable size of the allocation at `ptr`, which may be larger than the size
+    /// originally requested due to rounding up to mimalloc's internal size classes.
+    #[inline]
+    pub unsafe fn usable_size(&self, ptr: *const u8) -> usize {
+        mi_usable_size(ptr as *const c_void)
+    }
+}
+
+#[cfg(feature = "nightly")]
+unsafe impl Allocator for MiMalloc {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { allocate_impl(layout, false) }
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { allocate_impl(layout, true) }
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+            mi_free(ptr.as_ptr() as *mut c_void);
+        } else {
+            mi_free_size_aligned(ptr.as_ptr() as *mut c_void, layout.size(), layout.align());
+        }
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(new_layout.size() >= old_layout.size());
+        realloc_impl(ptr, new_layout)
     }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(new_layout.size() >= old_layout.size());
+        let new_ptr = realloc_impl(ptr, new_layout)?;
+        let raw = new_ptr.as_non_null_ptr().as_ptr();
+        raw.add(old_layout.size())
+            .write_bytes(0, new_ptr.len() - old_layout.size());
+        Ok(new_ptr)
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(new_layout.size() <= old_layout.size());
+        realloc_impl(ptr, new_layout)
+    }
+}
+
+#[cfg(feature = "nightly")]
+#[inline]
+unsafe fn allocate_impl(layout: Layout, zeroed: bool) -> Result<NonNull<[u8]>, AllocError> {
+    let raw = if zeroed {
+        mi_zalloc_aligned(layout.size(), layout.align())
+    } else {
+        mi_malloc_aligned(layout.size(), layout.align())
+    } as *mut u8;
+
+    let ptr = NonNull::new(raw).ok_or(AllocError)?;
+    let usable = mi_usable_size(raw as *const c_void);
+    Ok(NonNull::slice_from_raw_parts(ptr, usable))
+}
+
+#[cfg(feature = "nightly")]
+#[inline]
+unsafe fn realloc_impl(
+    ptr: NonNull<u8>,
+    new_layout: Layout,
+) -> Result<NonNull<[u8]>, AllocError> {
+    let raw = mi_realloc_aligned(
+        ptr.as_ptr() as *mut c_void,
+        new_layout.size(),
+        new_layout.align(),
+    ) as *mut u8;
+
+    let ptr = NonNull::new(raw).ok_or(AllocError)?;
+    let usable = mi_usable_size(raw as *const c_void);
+    Ok(NonNull::slice_from_raw_parts(ptr, usable))
 }
 
 #[cfg(test)]
@@ -142,3 +345,107 @@ mod tests {
         }
     }
 }
+
+#[cfg(all(test, feature = "nightly"))]
+mod allocator_api_tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn it_allocates_and_deallocates() {
+        unsafe {
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.allocate(layout).unwrap();
+            alloc.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_and_deallocates_big_memory() {
+        unsafe {
+            let layout = Layout::from_size_align(1 << 20, 32).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.allocate(layout).unwrap();
+            alloc.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_and_deallocates_zeroed_memory() {
+        unsafe {
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.allocate_zeroed(layout).unwrap();
+            alloc.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_and_deallocates_zeroed_big_memory() {
+        unsafe {
+            let layout = Layout::from_size_align(1 << 20, 32).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.allocate_zeroed(layout).unwrap();
+            alloc.deallocate(ptr.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn it_grows_allocated_memory() {
+        unsafe {
+            let old_layout = Layout::from_size_align(8, 8).unwrap();
+            let new_layout = Layout::from_size_align(16, 8).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.allocate(old_layout).unwrap();
+            let ptr = alloc
+                .grow(ptr.as_non_null_ptr(), old_layout, new_layout)
+                .unwrap();
+            alloc.deallocate(ptr.as_non_null_ptr(), new_layout);
+        }
+    }
+
+    #[test]
+    fn it_grows_zeroed_allocated_memory() {
+        unsafe {
+            let old_layout = Layout::from_size_align(8, 8).unwrap();
+            let new_layout = Layout::from_size_align(16, 8).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.allocate(old_layout).unwrap();
+            let ptr = alloc
+                .grow_zeroed(ptr.as_non_null_ptr(), old_layout, new_layout)
+                .unwrap();
+            alloc.deallocate(ptr.as_non_null_ptr(), new_layout);
+        }
+    }
+
+    #[test]
+    fn it_shrinks_allocated_memory() {
+        unsafe {
+            let old_layout = Layout::from_size_align(1 << 20, 32).unwrap();
+            let new_layout = Layout::from_size_align(8, 32).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.allocate(old_layout).unwrap();
+            let ptr = alloc
+                .shrink(ptr.as_non_null_ptr(), old_layout, new_layout)
+                .unwrap();
+            alloc.deallocate(ptr.as_non_null_ptr(), new_layout);
+        }
+    }
+
+    #[test]
+    fn it_backs_a_vec_via_new_in() {
+        let mut v: Vec<u8, MiMalloc> = Vec::new_in(MiMalloc);
+        v.extend_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(v, [1, 2, 3, 4]);
+    }
+}